@@ -3,110 +3,580 @@
 
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use tauri::Manager;
+use tauri::{AppHandle, Manager};
 use tauri_plugin_shell::ShellExt;
-use std::time::Duration;
-use std::sync::Mutex;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use tokio::sync::Notify;
+
+// Backoff schedule for respawning a crashed backend, and the point at which
+// the supervisor stops trying and reports `GaveUp`.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_CONSECUTIVE_FAILURES: u32 = 6;
+// A run that survives this long before crashing counts as healthy again, so a
+// backend that's merely flaky doesn't get permanently given up on.
+const HEALTHY_RUN_THRESHOLD: Duration = Duration::from_secs(30);
+
+// How long to wait for the backend to exit on its own after asking it to
+// shut down, before falling back to a hard kill.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "status")]
+enum BackendStatus {
+    Starting,
+    Ready,
+    Crashed,
+    Restarting { attempt: u32, delay_ms: u64 },
+    GaveUp,
+}
+
+#[derive(Clone, Serialize)]
+struct BackendReadyPayload {
+    url: String,
+}
+
+// Number of health-check attempts and the delay between them, overridable
+// for slow machines or CI without touching the defaults.
+fn health_check_attempts() -> u32 {
+    std::env::var("MYCELIUM_HEALTH_CHECK_ATTEMPTS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(30)
+}
+
+fn health_check_interval() -> Duration {
+    std::env::var("MYCELIUM_HEALTH_CHECK_INTERVAL_MS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .map(Duration::from_millis)
+        .unwrap_or(Duration::from_millis(500))
+}
+
+// Lets the webview tell the supervisor (via `frontend_ready`) that its DOM is
+// initialized, so the first health poll doesn't race window startup on slow
+// machines. Remembers the signal even if it arrives before anything waits on
+// it, and stays set across backend restarts.
+struct FrontendReadySignal {
+    ready: AtomicBool,
+    notify: Notify,
+}
+
+impl FrontendReadySignal {
+    fn new() -> Self {
+        Self { ready: AtomicBool::new(false), notify: Notify::new() }
+    }
+
+    fn mark_ready(&self) {
+        self.ready.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    async fn wait(&self) {
+        // Register for the notification before checking the flag, so a
+        // `mark_ready` landing between the two can't be missed.
+        let notified = self.notify.notified();
+        if self.ready.load(Ordering::SeqCst) {
+            return;
+        }
+        notified.await;
+    }
+}
 
 #[tauri::command]
-async fn get_backend_url() -> String {
-    "http://localhost:8000".to_string()
+fn frontend_ready(signal: tauri::State<FrontendReadySignal>) {
+    signal.mark_ready();
+}
+
+#[tauri::command]
+fn retry_backend(app_handle: AppHandle) {
+    // Goes through the same `ready_task` slot `supervise_backend` uses, so a
+    // manual retry and an automatic post-crash restart can't both be polling
+    // health concurrently and racing to emit contradicting events.
+    let ready_handle = app_handle.clone();
+    let ready_task = tauri::async_runtime::spawn(async move {
+        let base_url = ready_handle.state::<BackendUrl>().get();
+        wait_for_backend(&ready_handle, &base_url).await;
+    });
+    let backend_state = app_handle.state::<BackendProcess>();
+    if let Some(previous) = backend_state.ready_task.lock().unwrap().replace(ready_task) {
+        previous.abort();
+    }
+}
+
+// Maximum number of backend log lines retained for windows that subscribe late.
+const LOG_BACKLOG_CAPACITY: usize = 1000;
+
+#[derive(Clone, Serialize)]
+struct ConsoleEvent {
+    level: &'static str,
+    message: String,
+    timestamp: u64,
+}
+
+impl ConsoleEvent {
+    fn new(level: &'static str, message: String) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        Self { level, message, timestamp }
+    }
+}
+
+// Ring buffer of recent backend log lines, kept so newly opened windows can
+// catch up on output emitted before they started listening for `backend-log`.
+struct LogBuffer(Mutex<VecDeque<ConsoleEvent>>);
+
+impl LogBuffer {
+    fn push(&self, event: ConsoleEvent) {
+        let mut buffer = self.0.lock().unwrap();
+        if buffer.len() == LOG_BACKLOG_CAPACITY {
+            buffer.pop_front();
+        }
+        buffer.push_back(event);
+    }
+}
+
+// Base URL the backend sidecar was actually started on, resolved at launch
+// by probing a free port rather than assuming `localhost:8000` is free.
+// `ready`/`notify` let `get_backend_url` block until the port has actually
+// been resolved instead of ever handing back the empty placeholder.
+struct BackendUrl {
+    url: Mutex<String>,
+    ready: AtomicBool,
+    notify: Notify,
+}
+
+impl BackendUrl {
+    fn new() -> Self {
+        Self {
+            url: Mutex::new(String::new()),
+            ready: AtomicBool::new(false),
+            notify: Notify::new(),
+        }
+    }
+
+    fn set(&self, url: String) {
+        *self.url.lock().unwrap() = url;
+        self.ready.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    fn get(&self) -> String {
+        self.url.lock().unwrap().clone()
+    }
+
+    async fn wait_resolved(&self) {
+        let notified = self.notify.notified();
+        if self.ready.load(Ordering::SeqCst) {
+            return;
+        }
+        notified.await;
+    }
+}
+
+// Binds an OS-assigned port and returns it, then releases the listener so
+// the backend sidecar can bind it in turn. There's an inherent TOCTOU gap
+// between releasing the listener here and the sidecar binding the same port
+// a moment later - another process (including a concurrently launching
+// instance of this app) could grab it first. Good enough in practice since
+// the OS won't hand out the same ephemeral port again immediately, but this
+// isn't a hard guarantee; a fully race-free handoff would need to pass the
+// bound socket's file descriptor to the sidecar instead.
+fn pick_free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .expect("Failed to bind to an available port")
+        .local_addr()
+        .expect("Failed to read bound port")
+        .port()
+}
+
+#[tauri::command]
+async fn get_backend_url(backend_url: tauri::State<'_, BackendUrl>) -> Result<String, ()> {
+    backend_url.wait_resolved().await;
+    Ok(backend_url.get())
+}
+
+#[tauri::command]
+fn get_backend_logs(logs: tauri::State<LogBuffer>) -> Vec<ConsoleEvent> {
+    logs.0.lock().unwrap().iter().cloned().collect()
+}
+
+// How many trailing stderr lines to keep as breadcrumbs for crash reports.
+const BREADCRUMB_CAPACITY: usize = 50;
+
+// Opt-in crash reporting: captures Rust panics and backend crashes (stderr
+// breadcrumbs, `CommandEvent::Error`, and the exit status from
+// `CommandEvent::Terminated`) and forwards them to an external endpoint.
+// Disabled unless `MYCELIUM_ERROR_REPORTING_DSN` is set; the DSN being empty
+// always wins over the runtime consent flag.
+struct ErrorReporter {
+    dsn: String,
+    consent: Mutex<bool>,
+    breadcrumbs: Mutex<VecDeque<String>>,
+}
+
+#[derive(Serialize)]
+struct CrashReport {
+    message: String,
+    breadcrumbs: Vec<String>,
+}
+
+impl ErrorReporter {
+    fn new() -> Self {
+        let dsn = std::env::var("MYCELIUM_ERROR_REPORTING_DSN").unwrap_or_default();
+        let consent = !dsn.is_empty();
+        Self {
+            dsn,
+            consent: Mutex::new(consent),
+            breadcrumbs: Mutex::new(VecDeque::with_capacity(BREADCRUMB_CAPACITY)),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        !self.dsn.is_empty() && *self.consent.lock().unwrap()
+    }
+
+    fn set_consent(&self, consent: bool) {
+        *self.consent.lock().unwrap() = consent;
+    }
+
+    fn add_breadcrumb(&self, line: String) {
+        let mut crumbs = self.breadcrumbs.lock().unwrap();
+        if crumbs.len() == BREADCRUMB_CAPACITY {
+            crumbs.pop_front();
+        }
+        crumbs.push_back(line);
+    }
+
+    // Sends `message` plus the current breadcrumb trail to the configured DSN.
+    // Fire-and-forget on a plain thread so a crash report can never block
+    // shutdown or hold up the async runtime.
+    fn capture(&self, message: String) {
+        if !self.is_enabled() {
+            return;
+        }
+        let dsn = self.dsn.clone();
+        let breadcrumbs: Vec<String> = self.breadcrumbs.lock().unwrap().iter().cloned().collect();
+        std::thread::spawn(move || {
+            let report = CrashReport { message, breadcrumbs };
+            let body = match serde_json::to_string(&report) {
+                Ok(body) => body,
+                Err(e) => {
+                    eprintln!("Failed to serialize crash report: {}", e);
+                    return;
+                }
+            };
+            let result = reqwest::blocking::Client::new()
+                .post(&dsn)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .send();
+            if let Err(e) = result {
+                eprintln!("Failed to send crash report: {}", e);
+            }
+        });
+    }
+}
+
+#[tauri::command]
+fn set_telemetry_consent(enabled: bool, reporter: tauri::State<Arc<ErrorReporter>>) {
+    reporter.set_consent(enabled);
 }
 
 // Wrapper for the backend process with Mutex for thread-safe access
-struct BackendProcess(Mutex<Option<tauri_plugin_shell::process::CommandChild>>);
+struct BackendProcess {
+    child: Mutex<Option<tauri_plugin_shell::process::CommandChild>>,
+    // Set by the window-close handler so the supervisor knows a `Terminated`
+    // event was requested, not a crash, and shouldn't trigger a respawn.
+    shutdown_requested: Mutex<bool>,
+    // Set by the supervisor once it observes `CommandEvent::Terminated`, so
+    // the shutdown path can tell a graceful exit from a timeout without
+    // polling the OS directly.
+    exited: Mutex<bool>,
+    // Handle of the readiness poll (`wait_for_backend`) spawned for the
+    // current sidecar attempt. Aborted when a new attempt starts so a stale
+    // poll from a crashed attempt can't emit a contradicting `backend-ready`/
+    // `backend-unreachable` event after a later attempt has already settled.
+    ready_task: Mutex<Option<tauri::async_runtime::JoinHandle<()>>>,
+}
+
+impl BackendProcess {
+    fn new() -> Self {
+        Self {
+            child: Mutex::new(None),
+            shutdown_requested: Mutex::new(false),
+            exited: Mutex::new(false),
+            ready_task: Mutex::new(None),
+        }
+    }
+
+    fn is_shutting_down(&self) -> bool {
+        *self.shutdown_requested.lock().unwrap()
+    }
+
+    fn has_exited(&self) -> bool {
+        *self.exited.lock().unwrap()
+    }
+}
 
 fn main() {
+    let error_reporter = Arc::new(ErrorReporter::new());
+    {
+        let reporter = error_reporter.clone();
+        std::panic::set_hook(Box::new(move |info| {
+            eprintln!("{}", info);
+            reporter.capture(format!("panic: {}", info));
+        }));
+    }
+
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
+        .manage(LogBuffer(Mutex::new(VecDeque::with_capacity(LOG_BACKLOG_CAPACITY))))
+        .manage(BackendProcess::new())
+        .manage(BackendUrl::new())
+        .manage(error_reporter)
+        .manage(FrontendReadySignal::new())
         .setup(|app| {
             let app_handle = app.handle().clone();
-            
-            // Spawn the Python backend sidecar
-            tauri::async_runtime::spawn(async move {
-                println!("Starting Mycelium backend...");
-                
-                let shell = app_handle.shell();
-                
-                // Spawn the sidecar process
-                let sidecar_command = shell.sidecar("mycelium-backend")
-                    .expect("Failed to create sidecar command");
-                
-                let (mut rx, child) = sidecar_command
-                    .spawn()
-                    .expect("Failed to spawn backend sidecar");
-                
-                // Store the child process handle for cleanup (wrapped in Mutex)
-                app_handle.manage(BackendProcess(Mutex::new(Some(child))));
-                
-                // Log backend output
-                tauri::async_runtime::spawn(async move {
-                    use tauri_plugin_shell::process::CommandEvent;
-                    while let Some(event) = rx.recv().await {
-                        match event {
-                            CommandEvent::Stdout(line) => {
-                                println!("[Backend] {}", String::from_utf8_lossy(&line));
-                            }
-                            CommandEvent::Stderr(line) => {
-                                eprintln!("[Backend Error] {}", String::from_utf8_lossy(&line));
-                            }
-                            CommandEvent::Error(err) => {
-                                eprintln!("[Backend Fatal] {}", err);
-                            }
-                            CommandEvent::Terminated(status) => {
-                                println!("[Backend] Process terminated with status: {:?}", status);
-                                break;
-                            }
-                            _ => {}
-                        }
-                    }
-                });
-                
-                // Wait for backend to be ready
-                wait_for_backend().await;
-                println!("Backend is ready!");
-            });
-            
+            tauri::async_runtime::spawn(supervise_backend(app_handle));
             Ok(())
         })
         .on_window_event(|window, event| {
-            if let tauri::WindowEvent::CloseRequested { .. } = event {
-                println!("Window closing, shutting down backend...");
-                
-                // Kill the backend process
-                if let Some(backend) = window.try_state::<BackendProcess>() {
-                    if let Ok(mut guard) = backend.0.lock() {
-                        if let Some(child) = guard.take() {
-                            match child.kill() {
-                                Ok(_) => println!("Backend process killed successfully"),
-                                Err(e) => eprintln!("Failed to kill backend: {}", e),
-                            }
-                        }
-                    }
+            if let tauri::WindowEvent::CloseRequested { api, .. } = event {
+                // `window.close()` below re-emits `CloseRequested`; once the
+                // backend shutdown has run, let that close go through.
+                if window.app_handle().state::<BackendProcess>().is_shutting_down() {
+                    return;
                 }
+                println!("Window closing, shutting down backend...");
+
+                // Give the backend a chance to shut down cleanly before the
+                // window actually closes.
+                api.prevent_close();
+                let window = window.clone();
+                tauri::async_runtime::spawn(async move {
+                    shutdown_backend(window.app_handle()).await;
+                    let _ = window.close();
+                });
             }
         })
-        .invoke_handler(tauri::generate_handler![get_backend_url])
+        .invoke_handler(tauri::generate_handler![
+            get_backend_url,
+            get_backend_logs,
+            set_telemetry_consent,
+            frontend_ready,
+            retry_backend
+        ])
         .run(tauri::generate_context!())
         .expect("error while running Mycelium");
 }
 
-async fn wait_for_backend() {
+// Supervises the backend sidecar for the lifetime of the app: spawns it,
+// forwards its log lines, and respawns it with exponential backoff if it
+// terminates unexpectedly, giving up after too many rapid consecutive
+// failures to avoid a crash loop.
+async fn supervise_backend(app_handle: AppHandle) {
+    use tauri_plugin_shell::process::CommandEvent;
+
+    // Resolved once so the URL stays stable across restarts; only the sidecar
+    // process itself gets respawned, not the port it's bound to.
+    let port = pick_free_port();
+    let base_url = format!("http://127.0.0.1:{}", port);
+    app_handle.state::<BackendUrl>().set(base_url.clone());
+
+    let mut restart_count: u32 = 0;
+    let mut backoff = INITIAL_BACKOFF;
+
+    loop {
+        // The window-close handler may have requested shutdown while we were
+        // asleep waiting to respawn; don't spawn an orphaned sidecar that
+        // nothing will ever clean up (notably on macOS, where closing the
+        // last window doesn't end the process and no further
+        // `CloseRequested` event will fire).
+        if app_handle.state::<BackendProcess>().is_shutting_down() {
+            println!("Backend shut down intentionally, not restarting");
+            return;
+        }
+
+        let _ = app_handle.emit_all("backend-status", BackendStatus::Starting);
+        println!("Starting Mycelium backend on port {}...", port);
+
+        let shell = app_handle.shell();
+        let sidecar_command = match shell.sidecar("mycelium-backend") {
+            Ok(command) => command.args(["--port", &port.to_string()]),
+            Err(e) => {
+                eprintln!("Failed to create sidecar command: {}", e);
+                let _ = app_handle.emit_all("backend-status", BackendStatus::GaveUp);
+                return;
+            }
+        };
+
+        let (mut rx, child) = match sidecar_command.spawn() {
+            Ok(pair) => pair,
+            Err(e) => {
+                eprintln!("Failed to spawn backend sidecar: {}", e);
+                let _ = app_handle.emit_all("backend-status", BackendStatus::GaveUp);
+                return;
+            }
+        };
+
+        let started_at = Instant::now();
+        let backend_state = app_handle.state::<BackendProcess>();
+        *backend_state.child.lock().unwrap() = Some(child);
+        *backend_state.exited.lock().unwrap() = false;
+
+        // Wait for backend to be ready, concurrently with draining its output
+        // below. `FrontendReadySignal::wait` returns immediately once the
+        // frontend has already signaled readiness, so waiting on every
+        // attempt (not just the first) is cheap and still protects restarts
+        // that happen before the frontend ever got a chance to signal. A
+        // stale poll from a previous (crashed) attempt is aborted so it
+        // can't emit a contradicting status once this attempt settles.
+        let ready_handle = app_handle.clone();
+        let ready_url = base_url.clone();
+        let ready_task = tauri::async_runtime::spawn(async move {
+            ready_handle.state::<FrontendReadySignal>().wait().await;
+            wait_for_backend(&ready_handle, &ready_url).await;
+        });
+        if let Some(previous) = backend_state.ready_task.lock().unwrap().replace(ready_task) {
+            previous.abort();
+        }
+
+        // Drain backend output until it exits, forwarding each line as a
+        // `backend-log` event and keeping it in the ring buffer.
+        let logs = app_handle.state::<LogBuffer>();
+        let error_reporter = app_handle.state::<Arc<ErrorReporter>>();
+        while let Some(event) = rx.recv().await {
+            match event {
+                CommandEvent::Stdout(line) => {
+                    let message = String::from_utf8_lossy(&line).to_string();
+                    println!("[Backend] {}", message);
+                    let event = ConsoleEvent::new("stdout", message);
+                    logs.push(event.clone());
+                    let _ = app_handle.emit_all("backend-log", event);
+                }
+                CommandEvent::Stderr(line) => {
+                    let message = String::from_utf8_lossy(&line).to_string();
+                    eprintln!("[Backend Error] {}", message);
+                    error_reporter.add_breadcrumb(message.clone());
+                    let event = ConsoleEvent::new("stderr", message);
+                    logs.push(event.clone());
+                    let _ = app_handle.emit_all("backend-log", event);
+                }
+                CommandEvent::Error(err) => {
+                    eprintln!("[Backend Fatal] {}", err);
+                    error_reporter.capture(format!("backend error: {}", err));
+                }
+                CommandEvent::Terminated(status) => {
+                    println!("[Backend] Process terminated with status: {:?}", status);
+                    if !backend_state.is_shutting_down() {
+                        error_reporter.capture(format!("backend terminated unexpectedly: {:?}", status));
+                    }
+                    *app_handle.state::<BackendProcess>().exited.lock().unwrap() = true;
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        if app_handle.state::<BackendProcess>().is_shutting_down() {
+            println!("Backend shut down intentionally, not restarting");
+            return;
+        }
+
+        // A backend that ran for a while before dying gets a clean slate.
+        if started_at.elapsed() > HEALTHY_RUN_THRESHOLD {
+            restart_count = 0;
+            backoff = INITIAL_BACKOFF;
+        }
+
+        restart_count += 1;
+        let _ = app_handle.emit_all("backend-status", BackendStatus::Crashed);
+
+        if restart_count > MAX_CONSECUTIVE_FAILURES {
+            eprintln!("Backend crashed {} times in a row, giving up", restart_count);
+            let _ = app_handle.emit_all("backend-status", BackendStatus::GaveUp);
+            return;
+        }
+
+        let _ = app_handle.emit_all(
+            "backend-status",
+            BackendStatus::Restarting { attempt: restart_count, delay_ms: backoff.as_millis() as u64 },
+        );
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+// Asks the backend to shut down cleanly (so it can flush state, release
+// ports, clean up temp files, etc.), waits up to `SHUTDOWN_GRACE_PERIOD` for
+// it to exit on its own, and only force-kills it as a last resort.
+async fn shutdown_backend(app_handle: &AppHandle) {
+    let backend_state = app_handle.state::<BackendProcess>();
+    *backend_state.shutdown_requested.lock().unwrap() = true;
+
+    if backend_state.has_exited() {
+        return;
+    }
+
+    let base_url = app_handle.state::<BackendUrl>().get();
+    let client = reqwest::Client::new();
+    if let Err(e) = client.post(format!("{}/shutdown", base_url)).send().await {
+        eprintln!("Failed to request graceful backend shutdown: {}", e);
+    }
+
+    let deadline = Instant::now() + SHUTDOWN_GRACE_PERIOD;
+    while Instant::now() < deadline {
+        if backend_state.has_exited() {
+            println!("Backend exited gracefully");
+            return;
+        }
+        tokio::time::sleep(SHUTDOWN_POLL_INTERVAL).await;
+    }
+
+    eprintln!("Backend did not exit within grace period, killing");
+    if let Some(child) = backend_state.child.lock().unwrap().take() {
+        if let Err(e) = child.kill() {
+            eprintln!("Failed to kill backend: {}", e);
+        }
+    }
+}
+
+async fn wait_for_backend(app_handle: &AppHandle, base_url: &str) {
     let client = reqwest::Client::new();
-    let health_url = "http://localhost:8000/health";
-    
-    for i in 0..30 {
-        match client.get(health_url).send().await {
+    let health_url = format!("{}/health", base_url);
+    let attempts = health_check_attempts();
+    let interval = health_check_interval();
+
+    for i in 0..attempts {
+        match client.get(&health_url).send().await {
             Ok(response) if response.status().is_success() => {
+                println!("Backend is ready!");
+                let _ = app_handle.emit_all("backend-status", BackendStatus::Ready);
+                let _ = app_handle.emit_all(
+                    "backend-ready",
+                    BackendReadyPayload { url: base_url.to_string() },
+                );
                 return;
             }
             _ => {
                 if i % 5 == 0 {
                     println!("Waiting for backend... (attempt {})", i + 1);
                 }
-                tokio::time::sleep(Duration::from_millis(500)).await;
+                tokio::time::sleep(interval).await;
             }
         }
     }
-    eprintln!("Warning: Backend health check timed out after 15 seconds");
+    eprintln!(
+        "Warning: Backend health check timed out after {} attempts",
+        attempts
+    );
+    let _ = app_handle.emit_all("backend-unreachable", ());
 }